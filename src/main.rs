@@ -1,26 +1,128 @@
+use anyhow::Context;
 use anyhow::Result;
+use chrono::DateTime;
+use chrono::FixedOffset;
+use chrono::TimeZone;
+use chrono::Utc;
+use windows::core::PWSTR;
 use windows::core::BSTR;
 use windows::core::PCWSTR;
+use windows::Win32::Foundation::GetLastError;
+use windows::Win32::Foundation::ERROR_NO_MORE_ITEMS;
+use windows::Win32::Foundation::ERROR_SUCCESS;
 use windows::Win32::Security::PSECURITY_DESCRIPTOR;
 use windows::Win32::System::Com::CoCreateInstance;
 use windows::Win32::System::Com::CoInitializeEx;
 use windows::Win32::System::Com::CoInitializeSecurity;
+use windows::Win32::System::Com::CoSetProxyBlanket;
+use windows::Win32::System::Com::SAFEARRAY;
 use windows::Win32::System::Com::CLSCTX_INPROC_SERVER;
 use windows::Win32::System::Com::COINIT_MULTITHREADED;
 use windows::Win32::System::Com::EOAC_NONE;
 use windows::Win32::System::Com::RPC_C_AUTHN_LEVEL_DEFAULT;
 use windows::Win32::System::Com::RPC_C_IMP_LEVEL_IMPERSONATE;
+use windows::Win32::Networking::Clustering::ClusterCloseEnum;
+use windows::Win32::Networking::Clustering::ClusterEnum;
+use windows::Win32::Networking::Clustering::ClusterOpenEnum;
+use windows::Win32::Networking::Clustering::CloseCluster;
+use windows::Win32::Networking::Clustering::OpenCluster;
+use windows::Win32::Networking::Clustering::CLUSTER_ENUM_NODE;
 use windows::Win32::System::Com::VARIANT;
-use windows::Win32::System::Ole::VarFormat;
+use windows::Win32::System::Com::COAUTHIDENTITY;
+use windows::Win32::System::Ole::SafeArrayGetElement;
+use windows::Win32::System::Ole::SafeArrayGetLBound;
+use windows::Win32::System::Ole::SafeArrayGetUBound;
 use windows::Win32::System::Ole::VariantClear;
-use windows::Win32::System::Ole::VARFORMAT_FIRST_DAY_SYSTEMDEFAULT;
-use windows::Win32::System::Ole::VARFORMAT_FIRST_WEEK_SYSTEMDEFAULT;
+use windows::Win32::System::Rpc::RPC_C_AUTHN_WINNT;
+use windows::Win32::System::Rpc::RPC_C_AUTHZ_NONE;
 use windows::Win32::System::Wmi::IWbemClassObject;
 use windows::Win32::System::Wmi::IWbemLocator;
+use windows::Win32::System::Wmi::IWbemObjectTextSrc;
 use windows::Win32::System::Wmi::IWbemServices;
 use windows::Win32::System::Wmi::WbemLocator;
+use windows::Win32::System::Wmi::WbemObjectTextSrc;
 use windows::Win32::System::Wmi::WBEM_FLAG_FORWARD_ONLY;
 use windows::Win32::System::Wmi::WBEM_FLAG_RETURN_IMMEDIATELY;
+use windows::Win32::System::Wmi::WMI_OBJ_TEXT_CIM_DTD_2_0;
+use windows::Win32::System::Variant::VARIANT_BOOL;
+use windows::Win32::System::Variant::VT_BOOL;
+use windows::Win32::System::Variant::VT_BSTR;
+use windows::Win32::System::Variant::VT_EMPTY;
+use windows::Win32::System::Variant::VT_I4;
+use windows::Win32::System::Variant::VT_I8;
+use windows::Win32::System::Variant::VT_NULL;
+use windows::Win32::System::Variant::VT_R8;
+use windows::Win32::System::Variant::VT_UI4;
+use windows::Win32::System::Variant::VT_UI8;
+
+// CIMTYPE constants, see the WMI SDK's `wbemcli.h` / `WbemClient_v1.h`.
+const CIM_SINT16: i32 = 2;
+const CIM_SINT32: i32 = 3;
+const CIM_REAL32: i32 = 4;
+const CIM_REAL64: i32 = 5;
+const CIM_STRING: i32 = 8;
+const CIM_BOOLEAN: i32 = 11;
+const CIM_SINT8: i32 = 16;
+const CIM_UINT8: i32 = 17;
+const CIM_UINT16: i32 = 18;
+const CIM_UINT32: i32 = 19;
+const CIM_SINT64: i32 = 20;
+const CIM_UINT64: i32 = 21;
+const CIM_DATETIME: i32 = 101;
+const CIM_REFERENCE: i32 = 102;
+// OR'd into the CIMTYPE when the property is a SAFEARRAY of the base type.
+const CIM_FLAG_ARRAY: i32 = 0x2000;
+
+// COAUTHIDENTITY::Flags, see `rpcdce.h`: the User/Domain/Password buffers
+// below are UTF-16, not the ANSI default.
+const SEC_WINNT_AUTH_IDENTITY_UNICODE: u32 = 0x2;
+
+/// Parameters for connecting to a (possibly remote) Hyper-V WMI namespace.
+/// A `host` of `None` connects to the local machine with the caller's own
+/// credentials, matching the previous hardcoded behavior.
+#[derive(Debug, Clone, Default)]
+#[allow(dead_code)]
+struct ConnectOptions {
+    host: Option<String>,
+    namespace: String,
+    domain: String,
+    user: String,
+    password: String,
+    authority: String,
+}
+
+impl ConnectOptions {
+    fn local() -> Self {
+        ConnectOptions {
+            host: None,
+            namespace: "root\\virtualization\\v2".to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+/// A CIM property value, typed from the `CIMTYPE` WMI hands back alongside
+/// the `VARIANT`, rather than coerced through `VarFormat` into a string.
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+enum WmiValue {
+    Bool(bool),
+    I32(i32),
+    U32(u32),
+    I64(i64),
+    U64(u64),
+    R64(f64),
+    Str(String),
+    DateTime(DateTime<Utc>),
+    Array(Vec<WmiValue>),
+    Null,
+}
+
+impl Default for WmiValue {
+    fn default() -> Self {
+        WmiValue::Null
+    }
+}
 
 fn init_com() -> Result<()> {
     unsafe {
@@ -41,69 +143,238 @@ fn init_com() -> Result<()> {
     Ok(())
 }
 
-fn connect_hyperv_wmi() -> Result<IWbemServices> {
+fn connect_hyperv_wmi(options: &ConnectOptions) -> Result<IWbemServices> {
+    let resource_path = match &options.host {
+        Some(host) => format!("\\\\{host}\\{}", options.namespace),
+        None => options.namespace.clone(),
+    };
+    let user = match options.domain.is_empty() {
+        true => options.user.clone(),
+        false => format!("{}\\{}", options.domain, options.user),
+    };
+
     let server = unsafe {
         let locator: IWbemLocator = CoCreateInstance(&WbemLocator, None, CLSCTX_INPROC_SERVER)?;
-        locator.ConnectServer(
-            &BSTR::from("root\\virtualization\\v2"),
-            &BSTR::new(),
-            &BSTR::new(),
+        let server = locator.ConnectServer(
+            &BSTR::from(resource_path),
+            &BSTR::from(user),
+            &BSTR::from(options.password.as_str()),
             &BSTR::new(),
             0,
-            &BSTR::new(),
+            &BSTR::from(options.authority.as_str()),
             None,
-        )?
+        )?;
+
+        set_remote_proxy_blanket(&server, options)?;
+
+        server
     };
 
     Ok(server)
 }
 
-fn get_row_item(o: &IWbemClassObject, name: &str) -> Result<String> {
+/// Remote `IWbemServices` calls fail with access-denied unless the proxy is
+/// told which identity to authenticate with on every call, since
+/// `IWbemLocator::ConnectServer` only authenticates the initial connection.
+/// Not needed for local connections, for which it returns without doing anything.
+fn set_remote_proxy_blanket(server: &IWbemServices, options: &ConnectOptions) -> Result<()> {
+    if options.host.is_none() {
+        return Ok(());
+    }
+
+    let mut user: Vec<u16> = options.user.encode_utf16().collect();
+    let mut domain: Vec<u16> = options.domain.encode_utf16().collect();
+    let mut password: Vec<u16> = options.password.encode_utf16().collect();
+
+    let mut identity = COAUTHIDENTITY {
+        User: user.as_mut_ptr(),
+        UserLength: user.len() as u32,
+        Domain: domain.as_mut_ptr(),
+        DomainLength: domain.len() as u32,
+        Password: password.as_mut_ptr(),
+        PasswordLength: password.len() as u32,
+        Flags: SEC_WINNT_AUTH_IDENTITY_UNICODE,
+    };
+
+    unsafe {
+        CoSetProxyBlanket(
+            server,
+            RPC_C_AUTHN_WINNT,
+            RPC_C_AUTHZ_NONE,
+            None,
+            RPC_C_AUTHN_LEVEL_DEFAULT,
+            RPC_C_IMP_LEVEL_IMPERSONATE,
+            Some(&mut identity as *mut COAUTHIDENTITY as *const std::ffi::c_void),
+            EOAC_NONE,
+        )?;
+    }
+
+    Ok(())
+}
+
+fn get_row_item(o: &IWbemClassObject, name: &str) -> Result<WmiValue> {
     let mut value: VARIANT = Default::default();
-    let wide_name = name.encode_utf16().collect::<Vec<_>>().as_ptr();
+    let mut cim_type: i32 = 0;
+    let wide_name: Vec<u16> = name.encode_utf16().chain(std::iter::once(0)).collect();
     unsafe {
         o.Get(
-            PCWSTR(wide_name),
+            PCWSTR(wide_name.as_ptr()),
             0,
             &mut value,
-            std::ptr::null_mut(),
+            &mut cim_type,
             std::ptr::null_mut(),
         )?;
 
-        let bstr = VarFormat(
-            &value,
-            None,
-            VARFORMAT_FIRST_DAY_SYSTEMDEFAULT,
-            VARFORMAT_FIRST_WEEK_SYSTEMDEFAULT,
-            0,
-        )?;
-
+        let result = variant_to_wmi_value(&value, cim_type);
         VariantClear(&mut value)?;
-        Ok(String::from_utf16(bstr.as_wide())?)
+        result
+    }
+}
+
+/// Converts a `VARIANT`/`CIMTYPE` pair coming out of `IWbemClassObject::Get`
+/// into a [`WmiValue`], walking the `SAFEARRAY` when `CIM_FLAG_ARRAY` is set.
+fn variant_to_wmi_value(value: &VARIANT, cim_type: i32) -> Result<WmiValue> {
+    if cim_type & CIM_FLAG_ARRAY != 0 {
+        return safe_array_to_wmi_value(value, cim_type & !CIM_FLAG_ARRAY);
+    }
+
+    unsafe {
+        // WMI reports the property's declared CIMTYPE even when the value is
+        // null (the union is simply zeroed), so the `vt` tag - not the
+        // CIMTYPE - is what tells an unset property apart from a real zero.
+        let vt = value.Anonymous.Anonymous.vt;
+        if vt == VT_NULL || vt == VT_EMPTY {
+            return Ok(WmiValue::Null);
+        }
+
+        let v = &value.Anonymous.Anonymous.Anonymous;
+        Ok(match cim_type {
+            CIM_BOOLEAN => WmiValue::Bool(v.boolVal.as_bool()),
+            CIM_SINT8 => WmiValue::I32(v.cVal as i32),
+            CIM_UINT8 => WmiValue::U32(v.bVal as u32),
+            CIM_SINT16 => WmiValue::I32(v.iVal as i32),
+            CIM_UINT16 => WmiValue::U32(v.uiVal as u32),
+            CIM_SINT32 => WmiValue::I32(v.lVal),
+            CIM_UINT32 => WmiValue::U32(v.ulVal),
+            CIM_SINT64 => WmiValue::I64(v.llVal),
+            CIM_UINT64 => WmiValue::U64(v.ullVal),
+            CIM_REAL32 => WmiValue::R64(v.fltVal as f64),
+            CIM_REAL64 => WmiValue::R64(v.dblVal),
+            // A reference (e.g. a method's `Job` out-parameter) arrives as
+            // the referenced object's path string.
+            CIM_STRING | CIM_REFERENCE => WmiValue::Str(v.bstrVal.to_string()),
+            CIM_DATETIME => parse_cim_datetime(&v.bstrVal.to_string())?,
+            _ => WmiValue::Null,
+        })
+    }
+}
+
+/// Parses a DMTF `CIM_DATETIME` string (`yyyymmddHHMMSS.mmmmmmsUUU`, where
+/// `UUU` is the UTC offset in minutes) into a `chrono` `DateTime<Utc>`.
+fn parse_cim_datetime(s: &str) -> Result<WmiValue> {
+    if s.len() != 25 {
+        anyhow::bail!("malformed CIM_DATETIME value: {s}");
+    }
+
+    let (local, offset_minutes) = s.split_at(21);
+    let naive = chrono::NaiveDateTime::parse_from_str(local, "%Y%m%d%H%M%S%.6f")?;
+    let offset_minutes: i32 = offset_minutes.parse()?;
+    let offset = FixedOffset::east_opt(offset_minutes * 60)
+        .ok_or_else(|| anyhow::anyhow!("invalid CIM_DATETIME UTC offset in: {s}"))?;
+    let dt = offset
+        .from_local_datetime(&naive)
+        .single()
+        .ok_or_else(|| anyhow::anyhow!("ambiguous CIM_DATETIME value: {s}"))?;
+    Ok(WmiValue::DateTime(dt.with_timezone(&Utc)))
+}
+
+#[cfg(test)]
+mod cim_datetime_tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_well_formed_timestamp() {
+        let value = parse_cim_datetime("20240131123456.123456+000").unwrap();
+        let WmiValue::DateTime(dt) = value else {
+            panic!("expected WmiValue::DateTime, got {value:?}");
+        };
+        assert_eq!(dt.to_rfc3339(), "2024-01-31T12:34:56.123456+00:00");
+    }
+
+    #[test]
+    fn applies_a_nonzero_utc_offset() {
+        let value = parse_cim_datetime("20240131000000.000000-300").unwrap();
+        let WmiValue::DateTime(dt) = value else {
+            panic!("expected WmiValue::DateTime, got {value:?}");
+        };
+        // -300 minutes local-time offset means the UTC instant is 5 hours later.
+        assert_eq!(dt.to_rfc3339(), "2024-01-31T05:00:00+00:00");
+    }
+
+    #[test]
+    fn rejects_the_wrong_length() {
+        assert!(parse_cim_datetime("20240131123456.123456+00").is_err());
+        assert!(parse_cim_datetime("").is_err());
+    }
+
+    #[test]
+    fn rejects_a_non_numeric_offset() {
+        assert!(parse_cim_datetime("20240131123456.123456+abc").is_err());
+    }
+}
+
+/// Walks a `SAFEARRAY` VARIANT one element at a time via
+/// `SafeArrayGetLBound`/`SafeArrayGetUBound`/`SafeArrayGetElement`, decoding
+/// each element as `elem_cim_type`.
+fn safe_array_to_wmi_value(value: &VARIANT, elem_cim_type: i32) -> Result<WmiValue> {
+    unsafe {
+        let psa: *mut SAFEARRAY = value.Anonymous.Anonymous.Anonymous.parray;
+        if psa.is_null() {
+            return Ok(WmiValue::Null);
+        }
+
+        let lbound = SafeArrayGetLBound(psa, 1)?;
+        let ubound = SafeArrayGetUBound(psa, 1)?;
+
+        let mut items = Vec::with_capacity((ubound - lbound + 1).max(0) as usize);
+        for index in lbound..=ubound {
+            let mut element: VARIANT = Default::default();
+            SafeArrayGetElement(
+                psa,
+                &index,
+                &mut element as *mut VARIANT as *mut std::ffi::c_void,
+            )?;
+            let item = variant_to_wmi_value(&element, elem_cim_type);
+            VariantClear(&mut element)?;
+            items.push(item?);
+        }
+
+        Ok(WmiValue::Array(items))
     }
 }
 
 trait WmiRowConstructable<T> {
     fn from_row(row: &IWbemClassObject) -> Result<T>;
     fn query_one(key: &str) -> String;
+    fn query_all() -> String;
 }
 
 #[derive(Debug)]
 #[allow(dead_code)]
 struct HyperVVmSettings {
-    virtual_system_identifier: String,
-    configuration_data_root: String,
-    configuration_file: String,
-    firmware_file: String,
-    firmware_parameters: String,
-    guest_state_data_root: String,
-    guest_state_file: String,
-    guest_state_isolation_enabled: String,
-    guest_state_isolation_type: String,
-    is_saved: String,
-    virtual_system_sub_type: String,
-    secure_boot_enabled: String,
-    turn_off_on_guest_restart: String,
+    virtual_system_identifier: WmiValue,
+    configuration_data_root: WmiValue,
+    configuration_file: WmiValue,
+    firmware_file: WmiValue,
+    firmware_parameters: WmiValue,
+    guest_state_data_root: WmiValue,
+    guest_state_file: WmiValue,
+    guest_state_isolation_enabled: WmiValue,
+    guest_state_isolation_type: WmiValue,
+    is_saved: WmiValue,
+    virtual_system_sub_type: WmiValue,
+    secure_boot_enabled: WmiValue,
+    turn_off_on_guest_restart: WmiValue,
 }
 
 impl WmiRowConstructable<HyperVVmSettings> for HyperVVmSettings {
@@ -132,57 +403,61 @@ impl WmiRowConstructable<HyperVVmSettings> for HyperVVmSettings {
     fn query_one(key: &str) -> String {
         format!("SELECT * FROM Msvm_VirtualSystemSettingData WHERE ElementName='{key}'")
     }
+
+    fn query_all() -> String {
+        "SELECT * FROM Msvm_VirtualSystemSettingData".to_string()
+    }
 }
 
 #[derive(Debug)]
 #[allow(dead_code)]
 struct HyperVVmStatus {
-    available_requested_states: String,
-    caption: String,
-    communication_status: String,
-    creation_class_name: String,
-    dedicated: String,
-    description: String,
-    detailed_status: String,
-    element_name: String,
-    enabled_default: String,
-    enabled_state: String,
-    enhanced_session_mode_state: String,
-    failed_over_replication_type: String,
-    health_state: String,
-    hw_threads_per_core_realized: String,
-    identifying_descriptions: String,
-    install_date: String,
-    instance_id: String,
-    last_application_consistent_replication_time: String,
-    last_replication_time: String,
-    last_replication_type: String,
-    last_successful_backup_time: String,
-    name: String,
-    name_format: String,
-    number_of_numa_nodes: String,
-    on_time_in_milliseconds: String,
-    operating_status: String,
-    operational_status: String,
-    other_dedicated_descriptions: String,
-    other_enabled_state: String,
-    other_identifying_info: String,
-    power_management_capabilities: String,
-    primary_owner_contact: String,
-    primary_owner_name: String,
-    primary_status: String,
-    process_id: String,
-    replication_health: String,
-    replication_mode: String,
-    replication_state: String,
-    requested_state: String,
-    reset_capability: String,
-    roles: String,
-    status: String,
-    status_descriptions: String,
-    time_of_last_configuration_change: String,
-    time_of_last_state_change: String,
-    transitioning_to_state: String,
+    available_requested_states: WmiValue,
+    caption: WmiValue,
+    communication_status: WmiValue,
+    creation_class_name: WmiValue,
+    dedicated: WmiValue,
+    description: WmiValue,
+    detailed_status: WmiValue,
+    element_name: WmiValue,
+    enabled_default: WmiValue,
+    enabled_state: WmiValue,
+    enhanced_session_mode_state: WmiValue,
+    failed_over_replication_type: WmiValue,
+    health_state: WmiValue,
+    hw_threads_per_core_realized: WmiValue,
+    identifying_descriptions: WmiValue,
+    install_date: WmiValue,
+    instance_id: WmiValue,
+    last_application_consistent_replication_time: WmiValue,
+    last_replication_time: WmiValue,
+    last_replication_type: WmiValue,
+    last_successful_backup_time: WmiValue,
+    name: WmiValue,
+    name_format: WmiValue,
+    number_of_numa_nodes: WmiValue,
+    on_time_in_milliseconds: WmiValue,
+    operating_status: WmiValue,
+    operational_status: WmiValue,
+    other_dedicated_descriptions: WmiValue,
+    other_enabled_state: WmiValue,
+    other_identifying_info: WmiValue,
+    power_management_capabilities: WmiValue,
+    primary_owner_contact: WmiValue,
+    primary_owner_name: WmiValue,
+    primary_status: WmiValue,
+    process_id: WmiValue,
+    replication_health: WmiValue,
+    replication_mode: WmiValue,
+    replication_state: WmiValue,
+    requested_state: WmiValue,
+    reset_capability: WmiValue,
+    roles: WmiValue,
+    status: WmiValue,
+    status_descriptions: WmiValue,
+    time_of_last_configuration_change: WmiValue,
+    time_of_last_state_change: WmiValue,
+    transitioning_to_state: WmiValue,
 }
 
 impl WmiRowConstructable<HyperVVmStatus> for HyperVVmStatus {
@@ -254,8 +529,13 @@ impl WmiRowConstructable<HyperVVmStatus> for HyperVVmStatus {
     fn query_one(key: &str) -> String {
         format!("SELECT * FROM Msvm_ComputerSystem WHERE ElementName='{key}'")
     }
+
+    fn query_all() -> String {
+        "SELECT * FROM Msvm_ComputerSystem".to_string()
+    }
 }
 
+#[allow(dead_code)]
 fn query_one<T>(server: &IWbemServices, vm_name: &str) -> Result<T>
 where
     T: WmiRowConstructable<T>,
@@ -281,15 +561,632 @@ where
     }
 }
 
-fn main() -> Result<()> {
-    init_com()?;
+/// Like [`query_one`], but hands back the raw `IWbemClassObject` instead of
+/// a parsed snapshot, for callers (VM lifecycle control, settings
+/// write-back) that need to act on the instance itself.
+fn query_one_row<T>(server: &IWbemServices, vm_name: &str) -> Result<IWbemClassObject>
+where
+    T: WmiRowConstructable<T>,
+{
+    unsafe {
+        let query = T::query_one(vm_name);
+        let enumerator = server.ExecQuery(
+            &BSTR::from("WQL"),
+            &BSTR::from(query),
+            WBEM_FLAG_FORWARD_ONLY | WBEM_FLAG_RETURN_IMMEDIATELY,
+            None,
+        )?;
+
+        let mut row = [None; 1];
+        let mut returned = 0;
+        enumerator.Next(-1, &mut row, &mut returned).ok()?;
+        row[0].clone().context("Not found")
+    }
+}
+
+/// Enumerates every instance of `T`'s WMI class, not just one matched by
+/// `ElementName`, looping `IEnumWbemClassObject::Next` until it runs dry.
+fn query_all<T>(server: &IWbemServices) -> Result<Vec<T>>
+where
+    T: WmiRowConstructable<T>,
+{
+    unsafe {
+        let query = T::query_all();
+        let enumerator = server.ExecQuery(
+            &BSTR::from("WQL"),
+            &BSTR::from(query),
+            WBEM_FLAG_FORWARD_ONLY | WBEM_FLAG_RETURN_IMMEDIATELY,
+            None,
+        )?;
+
+        let mut rows = Vec::new();
+        loop {
+            let mut row = [None; 1];
+            let mut returned = 0;
+
+            enumerator.Next(-1, &mut row, &mut returned).ok()?;
+            if returned == 0 {
+                break;
+            }
+            if let Some(row) = &row[0] {
+                rows.push(T::from_row(row)?);
+            }
+        }
+
+        Ok(rows)
+    }
+}
+
+// Msvm_ComputerSystem.RequestStateChange's RequestedState parameter, see the
+// Hyper-V WMI v2 provider schema.
+const REQUESTED_STATE_ENABLED: i32 = 2;
+const REQUESTED_STATE_DISABLED: i32 = 3;
+const REQUESTED_STATE_RESET: i32 = 11;
+const REQUESTED_STATE_PAUSED: i32 = 32768;
+const REQUESTED_STATE_SUSPENDED: i32 = 32769;
+
+// RequestStateChange (and most other Msvm_*Service methods) return this
+// immediately and finish asynchronously via a Msvm_ConcreteJob reference.
+const WMI_JOB_STATUS_STARTED: u32 = 4096;
+
+// Msvm_ConcreteJob.JobState, see the CIM_ConcreteJob schema: values above
+// "Completed" are terminal failure/cancellation states.
+const JOB_STATE_COMPLETED: i32 = 7;
+
+// How long wait_for_job polls a stuck/orphaned Msvm_ConcreteJob before
+// giving up, rather than blocking its caller forever.
+const JOB_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+const JOB_POLL_MAX_ATTEMPTS: u32 = 1200; // 1200 * 250ms = 5 minutes
+
+/// Reads the `__PATH` system property WMI stamps on every instance, which
+/// `ExecMethod` needs to address the object the method runs against.
+fn instance_path(row: &IWbemClassObject) -> Result<String> {
+    match get_row_item(row, "__PATH")? {
+        WmiValue::Str(path) => Ok(path),
+        _ => anyhow::bail!("instance is missing its __PATH system property"),
+    }
+}
+
+/// Invokes `Msvm_ComputerSystem::RequestStateChange` against `instance_path`,
+/// waiting on the returned `Msvm_ConcreteJob` if the change is asynchronous.
+fn request_state_change(
+    server: &IWbemServices,
+    instance_path: &str,
+    requested_state: i32,
+) -> Result<()> {
+    unsafe {
+        let mut class_object: Option<IWbemClassObject> = None;
+        server.GetObject(
+            &BSTR::from("Msvm_ComputerSystem"),
+            0,
+            None,
+            Some(&mut class_object),
+            None,
+        )?;
+        let class_object = class_object.context("Msvm_ComputerSystem class not found")?;
+
+        let method_name: Vec<u16> = "RequestStateChange"
+            .encode_utf16()
+            .chain(std::iter::once(0))
+            .collect();
+        let mut in_signature: Option<IWbemClassObject> = None;
+        class_object.GetMethod(
+            PCWSTR(method_name.as_ptr()),
+            0,
+            &mut in_signature,
+            std::ptr::null_mut(),
+        )?;
+        let in_params = in_signature
+            .context("RequestStateChange has no in-parameter signature")?
+            .SpawnInstance(0)?;
+
+        let state_name: Vec<u16> = "RequestedState"
+            .encode_utf16()
+            .chain(std::iter::once(0))
+            .collect();
+        let mut state_value = wmi_value_to_variant(&WmiValue::I32(requested_state))?;
+        let put_result = in_params.Put(PCWSTR(state_name.as_ptr()), 0, &mut state_value, 0);
+        VariantClear(&mut state_value)?;
+        put_result?;
+
+        let mut out_params: Option<IWbemClassObject> = None;
+        server.ExecMethod(
+            &BSTR::from(instance_path),
+            &BSTR::from("RequestStateChange"),
+            0,
+            None,
+            Some(&in_params),
+            Some(&mut out_params),
+            None,
+        )?;
+        let out_params = out_params.context("RequestStateChange returned no out-parameters")?;
 
-    let server = connect_hyperv_wmi()?;
-    let status = query_one::<HyperVVmStatus>(&server, "alpine")?;
-    println!("{status:#?}");
+        match get_row_item(&out_params, "ReturnValue")? {
+            WmiValue::U32(0) => Ok(()),
+            WmiValue::U32(WMI_JOB_STATUS_STARTED) => {
+                match get_row_item(&out_params, "Job")? {
+                    WmiValue::Str(job_path) => wait_for_job(server, &job_path),
+                    _ => anyhow::bail!("RequestStateChange is pending but returned no Job reference"),
+                }
+            }
+            WmiValue::U32(code) => anyhow::bail!("RequestStateChange failed with code {code}"),
+            _ => anyhow::bail!("RequestStateChange: unexpected ReturnValue type"),
+        }
+    }
+}
+
+/// Polls a `Msvm_ConcreteJob`'s `JobState` until it leaves the running
+/// states, giving up after [`JOB_POLL_MAX_ATTEMPTS`] rather than blocking
+/// the caller forever on a stuck or orphaned job.
+fn wait_for_job(server: &IWbemServices, job_path: &str) -> Result<()> {
+    for _ in 0..JOB_POLL_MAX_ATTEMPTS {
+        let job = unsafe {
+            let mut job: Option<IWbemClassObject> = None;
+            server.GetObject(&BSTR::from(job_path), 0, None, Some(&mut job), None)?;
+            job.context("Msvm_ConcreteJob instance vanished while polling")?
+        };
+
+        let state = match get_row_item(&job, "JobState")? {
+            WmiValue::I32(v) => v,
+            WmiValue::U32(v) => v as i32,
+            _ => anyhow::bail!("Msvm_ConcreteJob.JobState has an unexpected type"),
+        };
+
+        if state == JOB_STATE_COMPLETED {
+            return Ok(());
+        }
+        if state > JOB_STATE_COMPLETED {
+            let error = match get_row_item(&job, "ErrorDescription") {
+                Ok(WmiValue::Str(s)) => s,
+                _ => "unknown error".to_string(),
+            };
+            anyhow::bail!("Msvm_ConcreteJob failed (JobState {state}): {error}");
+        }
 
-    let settings = query_one::<HyperVVmSettings>(&server, "alpine")?;
-    println!("{settings:#?}");
+        std::thread::sleep(JOB_POLL_INTERVAL);
+    }
+
+    anyhow::bail!(
+        "Msvm_ConcreteJob {job_path} did not complete within {:?}",
+        JOB_POLL_INTERVAL * JOB_POLL_MAX_ATTEMPTS
+    );
+}
+
+/// Powers on a VM (`RequestedState = Enabled`).
+fn start_vm(server: &IWbemServices, row: &IWbemClassObject) -> Result<()> {
+    request_state_change(server, &instance_path(row)?, REQUESTED_STATE_ENABLED)
+}
+
+/// Powers off a VM (`RequestedState = Disabled`).
+fn stop_vm(server: &IWbemServices, row: &IWbemClassObject) -> Result<()> {
+    request_state_change(server, &instance_path(row)?, REQUESTED_STATE_DISABLED)
+}
+
+/// Pauses a running VM (`RequestedState = Paused`).
+fn pause_vm(server: &IWbemServices, row: &IWbemClassObject) -> Result<()> {
+    request_state_change(server, &instance_path(row)?, REQUESTED_STATE_PAUSED)
+}
+
+/// Saves a VM's state and stops it (`RequestedState = Suspended`).
+fn save_vm(server: &IWbemServices, row: &IWbemClassObject) -> Result<()> {
+    request_state_change(server, &instance_path(row)?, REQUESTED_STATE_SUSPENDED)
+}
+
+/// Resets a running VM (`RequestedState = Reset`).
+fn reset_vm(server: &IWbemServices, row: &IWbemClassObject) -> Result<()> {
+    request_state_change(server, &instance_path(row)?, REQUESTED_STATE_RESET)
+}
+
+/// Dispatches `command` (one of `start`/`stop`/`pause`/`save`/`reset`)
+/// against the named VM's `Msvm_ComputerSystem` instance.
+fn change_vm_power_state(server: &IWbemServices, vm_name: &str, command: &str) -> Result<()> {
+    let row = query_one_row::<HyperVVmStatus>(server, vm_name)?;
+    match command {
+        "start" => start_vm(server, &row),
+        "stop" => stop_vm(server, &row),
+        "pause" => pause_vm(server, &row),
+        "save" => save_vm(server, &row),
+        "reset" => reset_vm(server, &row),
+        _ => unreachable!("change_vm_power_state called with unknown command {command:?}"),
+    }
+}
 
+/// Writes a [`WmiValue`] into a property of an already-fetched
+/// `IWbemClassObject`, the setter half of [`get_row_item`]. Only the scalar
+/// variants round-trip; `DateTime`/`Array`/`Null` have no write path yet.
+fn set_row_item(o: &IWbemClassObject, name: &str, value: &WmiValue) -> Result<()> {
+    let wide_name: Vec<u16> = name.encode_utf16().chain(std::iter::once(0)).collect();
+    let mut variant = wmi_value_to_variant(value)?;
+    let result = unsafe { o.Put(PCWSTR(wide_name.as_ptr()), 0, &mut variant, 0) };
+    unsafe { VariantClear(&mut variant)? };
+    result?;
     Ok(())
 }
+
+/// Builds a `VARIANT` from a [`WmiValue`], the inverse of
+/// [`variant_to_wmi_value`]. Uses the same raw `.Anonymous.Anonymous.*`
+/// field access as the read path rather than `VARIANT::from`, since this
+/// crate targets the `windows` releases where `VARIANT` is a plain
+/// `#[repr(C)]` struct with public fields and no `From` impls. The caller
+/// owns the returned `VARIANT` and must `VariantClear` it once done (`Put`
+/// copies it rather than taking ownership).
+fn wmi_value_to_variant(value: &WmiValue) -> Result<VARIANT> {
+    let mut variant: VARIANT = Default::default();
+    unsafe {
+        let v0 = &mut variant.Anonymous.Anonymous;
+        match value {
+            WmiValue::Bool(b) => {
+                v0.vt = VT_BOOL;
+                v0.Anonymous.boolVal = VARIANT_BOOL(if *b { -1 } else { 0 });
+            }
+            WmiValue::I32(i) => {
+                v0.vt = VT_I4;
+                v0.Anonymous.lVal = *i;
+            }
+            WmiValue::U32(u) => {
+                v0.vt = VT_UI4;
+                v0.Anonymous.ulVal = *u;
+            }
+            WmiValue::I64(i) => {
+                v0.vt = VT_I8;
+                v0.Anonymous.llVal = *i;
+            }
+            WmiValue::U64(u) => {
+                v0.vt = VT_UI8;
+                v0.Anonymous.ullVal = *u;
+            }
+            WmiValue::R64(f) => {
+                v0.vt = VT_R8;
+                v0.Anonymous.dblVal = *f;
+            }
+            WmiValue::Str(s) => {
+                v0.vt = VT_BSTR;
+                v0.Anonymous.bstrVal = std::mem::ManuallyDrop::new(BSTR::from(s.as_str()));
+            }
+            WmiValue::DateTime(_) | WmiValue::Array(_) | WmiValue::Null => {
+                anyhow::bail!("writing this WmiValue variant back to WMI is not supported")
+            }
+        }
+    }
+    Ok(variant)
+}
+
+/// Finds the single `Msvm_VirtualSystemManagementService` instance, the
+/// singleton service class that owns `ModifySystemSettings` and friends.
+fn management_service_path(server: &IWbemServices) -> Result<String> {
+    unsafe {
+        let enumerator = server.ExecQuery(
+            &BSTR::from("WQL"),
+            &BSTR::from("SELECT * FROM Msvm_VirtualSystemManagementService"),
+            WBEM_FLAG_FORWARD_ONLY | WBEM_FLAG_RETURN_IMMEDIATELY,
+            None,
+        )?;
+
+        let mut row = [None; 1];
+        let mut returned = 0;
+        enumerator.Next(-1, &mut row, &mut returned).ok()?;
+        match &row[0] {
+            Some(row) => instance_path(row),
+            None => anyhow::bail!("Msvm_VirtualSystemManagementService singleton not found"),
+        }
+    }
+}
+
+/// Serializes `o` to CIM-XML (WMI-DTD 2.0) via the `WbemObjectTextSrc`
+/// helper object. `Modify*Settings`/`Add*Settings` methods on the Hyper-V
+/// management services take their embedded-instance in-parameters in this
+/// format, not the MOF text `IWbemClassObject::GetObjectText` produces.
+fn object_to_cim_xml(o: &IWbemClassObject) -> Result<BSTR> {
+    unsafe {
+        let text_src: IWbemObjectTextSrc =
+            CoCreateInstance(&WbemObjectTextSrc, None, CLSCTX_INPROC_SERVER)?;
+        text_src.GetText(0, o, WMI_OBJ_TEXT_CIM_DTD_2_0, None)
+    }
+}
+
+/// Writes `settings_row` back via `Msvm_VirtualSystemManagementService::ModifySystemSettings`.
+/// `settings_row` should be a `Msvm_VirtualSystemSettingData` instance fetched
+/// through [`query_one_row`] and then edited with [`set_row_item`];
+/// it is serialized to CIM-XML via [`object_to_cim_xml`] and passed as the
+/// `SystemSettings` in-parameter of a method call addressed to the
+/// management service's `__PATH`.
+fn modify_system_settings(server: &IWbemServices, settings_row: &IWbemClassObject) -> Result<()> {
+    unsafe {
+        let cim_xml = object_to_cim_xml(settings_row)?;
+        let service_path = management_service_path(server)?;
+
+        let mut class_object: Option<IWbemClassObject> = None;
+        server.GetObject(
+            &BSTR::from("Msvm_VirtualSystemManagementService"),
+            0,
+            None,
+            Some(&mut class_object),
+            None,
+        )?;
+        let class_object =
+            class_object.context("Msvm_VirtualSystemManagementService class not found")?;
+
+        let method_name: Vec<u16> = "ModifySystemSettings"
+            .encode_utf16()
+            .chain(std::iter::once(0))
+            .collect();
+        let mut in_signature: Option<IWbemClassObject> = None;
+        class_object.GetMethod(
+            PCWSTR(method_name.as_ptr()),
+            0,
+            &mut in_signature,
+            std::ptr::null_mut(),
+        )?;
+        let in_params = in_signature
+            .context("ModifySystemSettings has no in-parameter signature")?
+            .SpawnInstance(0)?;
+
+        let param_name: Vec<u16> = "SystemSettings"
+            .encode_utf16()
+            .chain(std::iter::once(0))
+            .collect();
+        let mut settings_value = wmi_value_to_variant(&WmiValue::Str(cim_xml.to_string()))?;
+        let put_result =
+            in_params.Put(PCWSTR(param_name.as_ptr()), 0, &mut settings_value, 0);
+        VariantClear(&mut settings_value)?;
+        put_result?;
+
+        let mut out_params: Option<IWbemClassObject> = None;
+        server.ExecMethod(
+            &BSTR::from(service_path),
+            &BSTR::from("ModifySystemSettings"),
+            0,
+            None,
+            Some(&in_params),
+            Some(&mut out_params),
+            None,
+        )?;
+        let out_params = out_params.context("ModifySystemSettings returned no out-parameters")?;
+
+        match get_row_item(&out_params, "ReturnValue")? {
+            WmiValue::U32(0) => Ok(()),
+            WmiValue::U32(WMI_JOB_STATUS_STARTED) => match get_row_item(&out_params, "Job")? {
+                WmiValue::Str(job_path) => wait_for_job(server, &job_path),
+                _ => anyhow::bail!("ModifySystemSettings is pending but returned no Job reference"),
+            },
+            WmiValue::U32(code) => anyhow::bail!("ModifySystemSettings failed with code {code}"),
+            _ => anyhow::bail!("ModifySystemSettings: unexpected ReturnValue type"),
+        }
+    }
+}
+
+/// Parses `text` into a [`WmiValue`] of the same shape as `existing`, so a
+/// CLI-supplied string lands on the wire as the CIM type WMI actually
+/// declared for the property (e.g. `VT_BOOL` for `SecureBootEnabled`)
+/// instead of always going out as `VT_BSTR` and relying on WMI to coerce it.
+fn parse_wmi_value_like(existing: &WmiValue, text: &str) -> Result<WmiValue> {
+    match existing {
+        WmiValue::Bool(_) => Ok(WmiValue::Bool(
+            text.parse()
+                .with_context(|| format!("{text:?} is not a valid bool"))?,
+        )),
+        WmiValue::I32(_) => Ok(WmiValue::I32(
+            text.parse()
+                .with_context(|| format!("{text:?} is not a valid i32"))?,
+        )),
+        WmiValue::U32(_) => Ok(WmiValue::U32(
+            text.parse()
+                .with_context(|| format!("{text:?} is not a valid u32"))?,
+        )),
+        WmiValue::I64(_) => Ok(WmiValue::I64(
+            text.parse()
+                .with_context(|| format!("{text:?} is not a valid i64"))?,
+        )),
+        WmiValue::U64(_) => Ok(WmiValue::U64(
+            text.parse()
+                .with_context(|| format!("{text:?} is not a valid u64"))?,
+        )),
+        WmiValue::R64(_) => Ok(WmiValue::R64(
+            text.parse()
+                .with_context(|| format!("{text:?} is not a valid f64"))?,
+        )),
+        WmiValue::Str(_) | WmiValue::Null => Ok(WmiValue::Str(text.to_string())),
+        WmiValue::DateTime(_) | WmiValue::Array(_) => {
+            anyhow::bail!("setting DateTime/Array properties is not supported")
+        }
+    }
+}
+
+/// Sets a single `Msvm_VirtualSystemSettingData` property on `vm_name` and
+/// writes it back, i.e. [`set_row_item`] + [`modify_system_settings`] over a
+/// freshly fetched settings instance. `value` is parsed according to the
+/// property's existing declared type (e.g. `SecureBootEnabled` parses as a
+/// bool, `VirtualSystemSubType` stays a string) rather than always being
+/// sent as a string.
+fn set_vm_setting(server: &IWbemServices, vm_name: &str, property: &str, value: &str) -> Result<()> {
+    let settings_row = query_one_row::<HyperVVmSettings>(server, vm_name)?;
+    let existing = get_row_item(&settings_row, property)?;
+    let typed_value = parse_wmi_value_like(&existing, value)?;
+    set_row_item(&settings_row, property, &typed_value)?;
+    modify_system_settings(server, &settings_row)
+}
+
+/// A `HyperVVmStatus` tagged with the failover cluster node currently
+/// hosting it, as returned by [`query_cluster_vms`].
+#[derive(Debug)]
+struct ClusterVmStatus {
+    node: String,
+    status: HyperVVmStatus,
+}
+
+/// Enumerates the nodes of a Windows failover cluster via the clusapi
+/// `OpenCluster`/`ClusterOpenEnum`/`ClusterEnum` triad, filtering for
+/// `CLUSTER_ENUM_NODE`. `cluster_name` is the cluster's network name, or
+/// `None` to use the cluster the local machine is a member of.
+fn cluster_node_names(cluster_name: Option<&str>) -> Result<Vec<String>> {
+    unsafe {
+        let cluster_name_wide: Vec<u16> = cluster_name
+            .map(|name| name.encode_utf16().chain(std::iter::once(0)).collect())
+            .unwrap_or_default();
+        let cluster_name_ptr = if cluster_name.is_some() {
+            PCWSTR(cluster_name_wide.as_ptr())
+        } else {
+            PCWSTR::null()
+        };
+
+        // `HCLUSTER`/`HCLUSENUM` are `isize`-backed handle newtypes (unlike
+        // pointer-backed handles such as `HANDLE`) with no `is_invalid()`
+        // method; both APIs document a null handle (`.0 == 0`) as failure.
+        let cluster = OpenCluster(cluster_name_ptr);
+        if cluster.0 == 0 {
+            anyhow::bail!("OpenCluster failed: {:?}", GetLastError());
+        }
+
+        let enum_handle = ClusterOpenEnum(cluster, CLUSTER_ENUM_NODE.0 as u32);
+        if enum_handle.0 == 0 {
+            let error = GetLastError();
+            let _ = CloseCluster(cluster);
+            anyhow::bail!("ClusterOpenEnum failed: {error:?}");
+        }
+
+        let mut node_names = Vec::new();
+        let mut index = 0;
+        loop {
+            let mut name_buf = [0u16; 256];
+            let mut name_len = name_buf.len() as u32;
+            let mut object_type = CLUSTER_ENUM_NODE.0 as u32;
+
+            let result = ClusterEnum(
+                enum_handle,
+                index,
+                &mut object_type,
+                PWSTR(name_buf.as_mut_ptr()),
+                &mut name_len,
+            );
+            if result == ERROR_NO_MORE_ITEMS.0 {
+                break;
+            }
+            if result != ERROR_SUCCESS.0 {
+                let _ = ClusterCloseEnum(enum_handle);
+                let _ = CloseCluster(cluster);
+                anyhow::bail!("ClusterEnum failed with error {result}");
+            }
+
+            node_names.push(String::from_utf16_lossy(&name_buf[..name_len as usize]));
+            index += 1;
+        }
+
+        let _ = ClusterCloseEnum(enum_handle);
+        let _ = CloseCluster(cluster);
+
+        Ok(node_names)
+    }
+}
+
+/// Runs [`query_all`] against every node of a failover cluster, connecting
+/// to each node's `root\virtualization\v2` over WMI in turn and tagging the
+/// results with the node that produced them, so an operator can see VM
+/// placement across the whole cluster from one call. `credentials` supplies
+/// the domain/user/password/authority used to connect to every node (its
+/// `host` is ignored and overwritten with each node's name in turn).
+fn query_cluster_vms(
+    cluster_name: Option<&str>,
+    credentials: &ConnectOptions,
+) -> Result<Vec<ClusterVmStatus>> {
+    let node_names = cluster_node_names(cluster_name)?;
+
+    let mut cluster_vms = Vec::new();
+    for node in node_names {
+        let options = ConnectOptions {
+            host: Some(node.clone()),
+            namespace: "root\\virtualization\\v2".to_string(),
+            ..credentials.clone()
+        };
+        let server = connect_hyperv_wmi(&options)?;
+
+        cluster_vms.extend(
+            query_all::<HyperVVmStatus>(&server)?
+                .into_iter()
+                .map(|status| ClusterVmStatus {
+                    node: node.clone(),
+                    status,
+                }),
+        );
+    }
+
+    Ok(cluster_vms)
+}
+
+/// Parses `--host`/`--domain`/`--user`/`--password`/`--authority` out of
+/// `args`, returning the resulting [`ConnectOptions`] and the remaining
+/// positional arguments (e.g. a VM name).
+fn parse_connect_options(args: &[String]) -> (ConnectOptions, Vec<String>) {
+    let mut options = ConnectOptions::local();
+    let mut rest = Vec::new();
+
+    let mut iter = args.iter().cloned();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--host" => options.host = iter.next(),
+            "--domain" => options.domain = iter.next().unwrap_or_default(),
+            "--user" => options.user = iter.next().unwrap_or_default(),
+            "--password" => options.password = iter.next().unwrap_or_default(),
+            "--authority" => options.authority = iter.next().unwrap_or_default(),
+            _ => rest.push(arg),
+        }
+    }
+
+    (options, rest)
+}
+
+fn print_status(options: &ConnectOptions) -> Result<()> {
+    let server = connect_hyperv_wmi(options)?;
+
+    let all_status = query_all::<HyperVVmStatus>(&server)?;
+    println!("{all_status:#?}");
+
+    let all_settings = query_all::<HyperVVmSettings>(&server)?;
+    println!("{all_settings:#?}");
+
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    init_com()?;
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (command, command_args) = match args.split_first() {
+        Some((command, rest)) => (command.as_str(), rest),
+        None => ("status", [].as_slice()),
+    };
+
+    match command {
+        "status" => {
+            let (options, _) = parse_connect_options(command_args);
+            print_status(&options)
+        }
+        "start" | "stop" | "pause" | "save" | "reset" => {
+            let (options, rest) = parse_connect_options(command_args);
+            let vm_name = rest
+                .first()
+                .with_context(|| format!("usage: vm_settings [--host H ...] {command} <vm-name>"))?;
+            let server = connect_hyperv_wmi(&options)?;
+            change_vm_power_state(&server, vm_name, command)
+        }
+        "set" => {
+            let (options, rest) = parse_connect_options(command_args);
+            let usage = "usage: vm_settings [--host H ...] set <vm-name> <property> <value>";
+            let vm_name = rest.first().with_context(|| usage)?;
+            let property = rest.get(1).with_context(|| usage)?;
+            let value = rest.get(2).with_context(|| usage)?;
+            let server = connect_hyperv_wmi(&options)?;
+            set_vm_setting(&server, vm_name, property, value)
+        }
+        "cluster" => {
+            let (credentials, rest) = parse_connect_options(command_args);
+            let cluster_name = rest.first().map(String::as_str);
+            let cluster_vms = query_cluster_vms(cluster_name, &credentials)?;
+            println!("{cluster_vms:#?}");
+            Ok(())
+        }
+        other => anyhow::bail!(
+            "unknown command {other:?}; usage: vm_settings [--host H --domain D --user U \
+             --password P --authority A] <status|start|stop|pause|save|reset|set|cluster> [args...]"
+        ),
+    }
+}